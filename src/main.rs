@@ -1,4 +1,4 @@
-use rsvm::vm::{Assembler, Instruction, VirtualMachine};
+use rsvm::vm::{Assembler, Instruction, StdOutput, VirtualMachine};
 
 fn main() {
     // Program that calculates (6 * 5 + 4 - 3 - 1) / 2
@@ -23,5 +23,6 @@ fn main() {
     assembler.assemble(instructions);
     let mut vm = VirtualMachine::new(1024, 1024);
     vm.load_program(&assembler.machine_code);
-    vm.execute();
+    vm.execute(&mut Vec::<i64>::new(), &mut StdOutput)
+        .expect("program should not fault");
 }
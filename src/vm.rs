@@ -1,9 +1,109 @@
-use std::convert::From;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// A source of values fed into a running program via the `Input` opcode.
+/// `read` returns `None` when no value is currently available, which
+/// suspends execution rather than blocking or panicking.
+pub trait Input {
+    fn read(&mut self) -> Option<i64>;
+}
+
+/// A sink for values emitted by a running program via `Print`/`Output`.
+pub trait Output {
+    fn write(&mut self, value: i64);
+}
+
+impl Input for Vec<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.pop()
+    }
+}
+
+impl Output for Vec<i64> {
+    fn write(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+/// An `Output` that writes through to stdout, matching the VM's previous
+/// hardcoded `println!` behavior.
+pub struct StdOutput;
+
+impl Output for StdOutput {
+    fn write(&mut self, value: i64) {
+        println!("{}", value);
+    }
+}
+
+/// The outcome of a call to `execute`: either the program ran to
+/// completion, or it hit an `Input` opcode with nothing to read and
+/// suspended. A subsequent `execute` call resumes from the same `ip`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ComputeResult {
+    Halted,
+    NeedsInput,
+}
+
+/// The outcome of a single `step` call, mirroring `ComputeResult` but
+/// distinguishing an in-progress single step from a terminal one.
+#[derive(Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    Stepped,
+    Halted,
+    NeedsInput,
+}
+
+/// Every way a guest program can fail. `execute` surfaces these instead of
+/// panicking so a crashing guest can't take down the host process.
+#[derive(Debug, PartialEq, Eq)]
+pub enum VmError {
+    StackUnderflow,
+    DivisionByZero,
+    InvalidOpcode(i64),
+    OutOfBounds { ip: i64, addr: i64 },
+    IntegerOverflow,
+    OutOfGas,
+    InvalidRegister(i64),
+    InvalidSnapshot,
+    InvalidSyscall(i64),
+    InvalidParamMode(i64),
+    InvalidWriteTarget,
+}
+
+/// Number of general-purpose registers in the VM's register file.
+const NUM_REGISTERS: usize = 16;
+
+/// Halts the machine, like the `Halt` opcode.
+pub const SYSCALL_SHUTDOWN: i64 = 0;
+/// Pops the top of stack and writes it through `Output`.
+pub const SYSCALL_WRITE: i64 = 1;
+/// Reads a value from `Input` and pushes it, suspending on `NeedsInput`
+/// if none is available yet, exactly like the `Input` opcode.
+pub const SYSCALL_READ: i64 = 2;
+
+/// What a syscall handler tells `Syscall` dispatch to do next: keep
+/// going, halt the machine, or suspend waiting on `Input` (mirroring
+/// `StepOutcome`, but scoped to what a handler can request).
+pub enum SyscallOutcome {
+    Continue,
+    Halt,
+    NeedsInput,
+}
+
+/// A host-installed handler for a syscall number, invoked with the VM and
+/// the same `Input`/`Output` the running `execute`/`step` call was given.
+pub type SyscallHandler =
+    Box<dyn FnMut(&mut VirtualMachine, &mut dyn Input, &mut dyn Output) -> Result<SyscallOutcome, VmError>>;
 
 pub struct VirtualMachine {
     ip: i64,
+    relative_base: i64,
     stack: Vec<i64>,
     memory: Vec<i64>,
+    registers: [i64; NUM_REGISTERS],
+    gas_limit: Option<u64>,
+    gas_used: u64,
+    syscalls: HashMap<i64, SyscallHandler>,
 }
 
 pub enum OpCode {
@@ -26,35 +126,126 @@ pub enum OpCode {
     Print = 0x10,
     Store = 0x11,
     Load = 0x12,
+    AdjustRelativeBase = 0x13,
+    Input = 0x14,
+    Output = 0x15,
+    LoadReg = 0x16,
+    StoreReg = 0x17,
+    MovRegImm = 0x18,
+    LoadByte = 0x19,
+    LoadHalf = 0x1A,
+    LoadWord = 0x1B,
+    StoreByte = 0x1C,
+    StoreHalf = 0x1D,
+    StoreWord = 0x1E,
+    Syscall = 0x1F,
+}
+
+impl TryFrom<i64> for OpCode {
+    type Error = VmError;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(OpCode::Halt),
+            0x01 => Ok(OpCode::Push),
+            0x02 => Ok(OpCode::Pop),
+            0x03 => Ok(OpCode::Add),
+            0x04 => Ok(OpCode::Sub),
+            0x05 => Ok(OpCode::Mul),
+            0x06 => Ok(OpCode::Div),
+            0x07 => Ok(OpCode::Jump),
+            0x08 => Ok(OpCode::JumpIfEqual),
+            0x09 => Ok(OpCode::JumpIfNotEqual),
+            0x0A => Ok(OpCode::JumpIfLessThan),
+            0x0B => Ok(OpCode::JumpIfGreaterThan),
+            0x0C => Ok(OpCode::JumpIfLessThanOrEqual),
+            0x0D => Ok(OpCode::JumpIfGreaterThanOrEqual),
+            0x0E => Ok(OpCode::Call),
+            0x0F => Ok(OpCode::Return),
+            0x10 => Ok(OpCode::Print),
+            0x11 => Ok(OpCode::Store),
+            0x12 => Ok(OpCode::Load),
+            0x13 => Ok(OpCode::AdjustRelativeBase),
+            0x14 => Ok(OpCode::Input),
+            0x15 => Ok(OpCode::Output),
+            0x16 => Ok(OpCode::LoadReg),
+            0x17 => Ok(OpCode::StoreReg),
+            0x18 => Ok(OpCode::MovRegImm),
+            0x19 => Ok(OpCode::LoadByte),
+            0x1A => Ok(OpCode::LoadHalf),
+            0x1B => Ok(OpCode::LoadWord),
+            0x1C => Ok(OpCode::StoreByte),
+            0x1D => Ok(OpCode::StoreHalf),
+            0x1E => Ok(OpCode::StoreWord),
+            0x1F => Ok(OpCode::Syscall),
+            _ => Err(VmError::InvalidOpcode(value)),
+        }
+    }
+}
+
+/// The gas cost of dispatching one instruction, following the EVM-style
+/// metering model: most opcodes are a flat 1, while the ones that do
+/// relatively more work (`Mul`/`Div`'s full multiply/divide, `Call`'s
+/// stack push plus jump) cost more.
+fn gas_cost(op: &OpCode) -> u64 {
+    match op {
+        OpCode::Mul | OpCode::Div => 5,
+        OpCode::Call => 3,
+        _ => 1,
+    }
 }
 
-impl From<i64> for OpCode {
-    fn from(value: i64) -> Self {
+/// The addressing mode of a single operand, encoded in the high digits of
+/// the opcode word (e.g. `1005` is `Mul` with modes `Position, Immediate`).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ParamMode {
+    /// Operand is a memory address to dereference.
+    Position,
+    /// Operand is used as-is.
+    Immediate,
+    /// Operand is a memory address relative to `relative_base`.
+    Relative,
+}
+
+impl TryFrom<i64> for ParamMode {
+    type Error = VmError;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
         match value {
-            0x00 => OpCode::Halt,
-            0x01 => OpCode::Push,
-            0x02 => OpCode::Pop,
-            0x03 => OpCode::Add,
-            0x04 => OpCode::Sub,
-            0x05 => OpCode::Mul,
-            0x06 => OpCode::Div,
-            0x07 => OpCode::Jump,
-            0x08 => OpCode::JumpIfEqual,
-            0x09 => OpCode::JumpIfNotEqual,
-            0x0A => OpCode::JumpIfLessThan,
-            0x0B => OpCode::JumpIfGreaterThan,
-            0x0C => OpCode::JumpIfLessThanOrEqual,
-            0x0D => OpCode::JumpIfGreaterThanOrEqual,
-            0x0E => OpCode::Call,
-            0x0F => OpCode::Return,
-            0x10 => OpCode::Print,
-            0x11 => OpCode::Store,
-            0x12 => OpCode::Load,
-            _ => panic!("Unknown opcode: {}", value),
+            0 => Ok(ParamMode::Position),
+            1 => Ok(ParamMode::Immediate),
+            2 => Ok(ParamMode::Relative),
+            _ => Err(VmError::InvalidParamMode(value)),
         }
     }
 }
 
+/// Splits a decoded opcode word into the base opcode and its mode digits,
+/// à la Intcode: `op = code % 100`, modes are the remaining digits from
+/// least to most significant.
+fn decode(code: i64) -> Result<(i64, [ParamMode; 3]), VmError> {
+    let op = code % 100;
+    let modes = [
+        ParamMode::try_from((code / 100) % 10)?,
+        ParamMode::try_from((code / 1000) % 10)?,
+        ParamMode::try_from((code / 10000) % 10)?,
+    ];
+    Ok((op, modes))
+}
+
+/// Keeps the low `bits` bits of `value`, zeroing the rest — used when
+/// storing a sub-word value into a memory cell.
+fn truncate_bits(value: i64, bits: u32) -> i64 {
+    value & ((1i64 << bits) - 1)
+}
+
+/// Sign-extends the low `bits` bits of `value` to a full `i64` — used when
+/// loading a sub-word value back out of a memory cell.
+fn sign_extend_bits(value: i64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    (value << shift) >> shift
+}
+
 pub enum Instruction {
     Push(i64),
     Pop,
@@ -62,30 +253,113 @@ pub enum Instruction {
     Sub,
     Mul,
     Div,
-    Jump(i64),
-    JumpIfEqual(i64),
-    JumpIfNotEqual(i64),
-    JumpIfLessThan(i64),
-    JumpIfGreaterThan(i64),
-    JumpIfLessThanOrEqual(i64),
-    JumpIfGreaterThanOrEqual(i64),
-    Call(i64),
+    Jump(i64, ParamMode),
+    JumpIfEqual(i64, ParamMode),
+    JumpIfNotEqual(i64, ParamMode),
+    JumpIfLessThan(i64, ParamMode),
+    JumpIfGreaterThan(i64, ParamMode),
+    JumpIfLessThanOrEqual(i64, ParamMode),
+    JumpIfGreaterThanOrEqual(i64, ParamMode),
+    Call(i64, ParamMode),
     Return,
     Print,
-    Store(i64),
-    Load(i64),
+    Store(i64, ParamMode),
+    Load(i64, ParamMode),
+    AdjustRelativeBase,
+    Input,
+    Output,
+    LoadReg(i64),
+    StoreReg(i64),
+    MovRegImm(i64, i64),
+    LoadByte(i64, ParamMode),
+    LoadHalf(i64, ParamMode),
+    LoadWord(i64, ParamMode),
+    StoreByte(i64, ParamMode),
+    StoreHalf(i64, ParamMode),
+    StoreWord(i64, ParamMode),
+    Syscall,
     Halt,
 }
 
 impl VirtualMachine {
     pub fn new(stack_size: i64, memory_size: i64) -> VirtualMachine {
-        VirtualMachine {
+        let mut vm = VirtualMachine {
             ip: 0,
+            relative_base: 0,
             stack: vec![0; usize::try_from(stack_size).unwrap().to_owned()],
             memory: vec![0; usize::try_from(memory_size).unwrap().to_owned()],
+            registers: [0; NUM_REGISTERS],
+            gas_limit: None,
+            gas_used: 0,
+            syscalls: HashMap::new(),
+        };
+        vm.register_syscall(SYSCALL_SHUTDOWN, |_, _, _| Ok(SyscallOutcome::Halt));
+        vm.register_syscall(SYSCALL_WRITE, |vm, _input, output| {
+            let value = vm.pop()?;
+            output.write(value);
+            Ok(SyscallOutcome::Continue)
+        });
+        vm.register_syscall(SYSCALL_READ, |vm, input, _output| match input.read() {
+            Some(value) => {
+                vm.stack.push(value);
+                Ok(SyscallOutcome::Continue)
+            }
+            None => Ok(SyscallOutcome::NeedsInput),
+        });
+        vm
+    }
+
+    /// Installs (or replaces) the handler invoked when a guest program
+    /// executes `Syscall` with `num` on top of stack. `SYSCALL_SHUTDOWN`,
+    /// `SYSCALL_WRITE`, and `SYSCALL_READ` are registered by `new`; hosts
+    /// can override them or add their own numbers.
+    pub fn register_syscall<F>(&mut self, num: i64, handler: F)
+    where
+        F: FnMut(&mut VirtualMachine, &mut dyn Input, &mut dyn Output) -> Result<SyscallOutcome, VmError>
+            + 'static,
+    {
+        self.syscalls.insert(num, Box::new(handler));
+    }
+
+    /// Like `new`, but bounds total execution to `gas_limit` units of work,
+    /// charged per dispatched instruction per `gas_cost`. `execute` returns
+    /// `VmError::OutOfGas` once the budget would be exceeded, which keeps an
+    /// untrusted or buggy program from looping the host forever.
+    pub fn new_with_gas(stack_size: i64, memory_size: i64, gas_limit: u64) -> VirtualMachine {
+        VirtualMachine {
+            gas_limit: Some(gas_limit),
+            ..VirtualMachine::new(stack_size, memory_size)
+        }
+    }
+
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+
+    /// Captures the full machine state so it can be resumed later, either
+    /// in-process via `restore` or persisted via `VmSnapshot::to_bytes`.
+    pub fn snapshot(&self) -> VmSnapshot {
+        VmSnapshot {
+            ip: self.ip,
+            relative_base: self.relative_base,
+            registers: self.registers,
+            stack: self.stack.clone(),
+            memory: self.memory.clone(),
+            gas_used: self.gas_used,
         }
     }
 
+    /// Replaces the machine's state with a previously captured `snapshot`.
+    /// `gas_limit` is configuration, not state, and is left untouched.
+    pub fn restore(&mut self, snapshot: &VmSnapshot) {
+        self.ip = snapshot.ip;
+        self.relative_base = snapshot.relative_base;
+        self.registers = snapshot.registers;
+        self.stack = snapshot.stack.clone();
+        self.memory = snapshot.memory.clone();
+        self.gas_used = snapshot.gas_used;
+    }
+
     pub fn load_program(&mut self, program: &[i64]) {
         assert!(program.len() <= self.memory.len());
         for (i, &instruction) in program.iter().enumerate() {
@@ -97,142 +371,453 @@ impl VirtualMachine {
         usize::try_from(value).unwrap().to_owned()
     }
 
-    pub fn execute(&mut self) {
+    fn pop(&mut self) -> Result<i64, VmError> {
+        self.stack.pop().ok_or(VmError::StackUnderflow)
+    }
+
+    fn mem_read(&self, addr: i64) -> Result<i64, VmError> {
+        usize::try_from(addr)
+            .ok()
+            .and_then(|i| self.memory.get(i))
+            .copied()
+            .ok_or(VmError::OutOfBounds { ip: self.ip, addr })
+    }
+
+    fn mem_write(&mut self, addr: i64, value: i64) -> Result<(), VmError> {
+        let ip = self.ip;
+        let slot = usize::try_from(addr)
+            .ok()
+            .and_then(|i| self.memory.get_mut(i))
+            .ok_or(VmError::OutOfBounds { ip, addr })?;
+        *slot = value;
+        Ok(())
+    }
+
+    /// Validates a register operand, mapping it to an index into
+    /// `registers` or `VmError::InvalidRegister` if it names no register.
+    fn reg_index(&self, r: i64) -> Result<usize, VmError> {
+        usize::try_from(r)
+            .ok()
+            .filter(|&i| i < NUM_REGISTERS)
+            .ok_or(VmError::InvalidRegister(r))
+    }
+
+    /// Reads the operand at `self.ip + offset`, interpreting it according
+    /// to `mode`: immediate values are used as-is, position values are
+    /// dereferenced through `memory`, and relative values are dereferenced
+    /// through `memory` offset by `relative_base`.
+    fn fetch_param(&self, mode: ParamMode, offset: i64) -> Result<i64, VmError> {
+        let raw = self.mem_read(self.ip + offset)?;
+        match mode {
+            ParamMode::Immediate => Ok(raw),
+            ParamMode::Position => self.mem_read(raw),
+            ParamMode::Relative => self.mem_read(self.relative_base + raw),
+        }
+    }
+
+    /// Resolves the write address of the operand at `self.ip + offset`,
+    /// applying `relative_base` when the mode is `Relative`. Immediate mode
+    /// is not a valid write target and is rejected with `VmError`.
+    fn write_addr(&self, mode: ParamMode, offset: i64) -> Result<i64, VmError> {
+        let raw = self.mem_read(self.ip + offset)?;
+        match mode {
+            ParamMode::Position => Ok(raw),
+            ParamMode::Relative => Ok(self.relative_base + raw),
+            ParamMode::Immediate => Err(VmError::InvalidWriteTarget),
+        }
+    }
+
+    /// Runs until the program halts or suspends on a dry `Input` opcode.
+    /// `ip` is a VM field, so calling `execute` again after `NeedsInput`
+    /// (once `input` has data) resumes at the instruction that asked for
+    /// it, letting a host drive the VM like a coroutine. Returns `Err` on
+    /// any malformed-program condition instead of panicking, including
+    /// `VmError::OutOfGas` once a configured `gas_limit` is exhausted.
+    pub fn execute(
+        &mut self,
+        input: &mut dyn Input,
+        output: &mut dyn Output,
+    ) -> Result<ComputeResult, VmError> {
         loop {
-            let opcode = self.memory[self.ip as usize];
-            match opcode.into() {
-                OpCode::Halt => {
-                    break;
+            match self.step(input, output)? {
+                StepOutcome::Stepped => continue,
+                StepOutcome::Halted => return Ok(ComputeResult::Halted),
+                StepOutcome::NeedsInput => return Ok(ComputeResult::NeedsInput),
+            }
+        }
+    }
+
+    /// Executes exactly one instruction and returns without looping,
+    /// unlike `execute`. Lets a host single-step a program (e.g. for a
+    /// debugger) while sharing the same dispatch and gas accounting.
+    pub fn step(
+        &mut self,
+        input: &mut dyn Input,
+        output: &mut dyn Output,
+    ) -> Result<StepOutcome, VmError> {
+        let (op, modes) = decode(self.mem_read(self.ip)?)?;
+        let opcode = OpCode::try_from(op)?;
+        if let Some(limit) = self.gas_limit {
+            if self.gas_used + gas_cost(&opcode) > limit {
+                return Err(VmError::OutOfGas);
+            }
+        }
+        self.gas_used += gas_cost(&opcode);
+        match opcode {
+            OpCode::Halt => {
+                return Ok(StepOutcome::Halted);
+            }
+            OpCode::Push => {
+                let value = self.mem_read(self.ip + 1)?;
+                self.stack.push(value);
+                self.ip += 2;
+            }
+            OpCode::Pop => {
+                self.pop()?;
+                self.ip += 1;
+            }
+            OpCode::Add => {
+                let op1 = self.pop()?;
+                let op2 = self.pop()?;
+                let result = op1.checked_add(op2).ok_or(VmError::IntegerOverflow)?;
+                self.stack.push(result);
+                self.ip += 1;
+            }
+            OpCode::Sub => {
+                let op1 = self.pop()?;
+                let op2 = self.pop()?;
+                let result = op2.checked_sub(op1).ok_or(VmError::IntegerOverflow)?;
+                self.stack.push(result);
+                self.ip += 1;
+            }
+            OpCode::Mul => {
+                let op1 = self.pop()?;
+                let op2 = self.pop()?;
+                let result = op1.checked_mul(op2).ok_or(VmError::IntegerOverflow)?;
+                self.stack.push(result);
+                self.ip += 1;
+            }
+            OpCode::Div => {
+                let op1 = self.pop()?;
+                let op2 = self.pop()?;
+                if op1 == 0 {
+                    return Err(VmError::DivisionByZero);
                 }
-                OpCode::Push => {
-                    let value = self.memory[(self.ip + 1) as usize];
-                    self.stack.push(value);
+                let result = op2.checked_div(op1).ok_or(VmError::IntegerOverflow)?;
+                self.stack.push(result);
+                self.ip += 1;
+            }
+            OpCode::Jump => {
+                let address = self.fetch_param(modes[0], 1)?;
+                self.ip = address;
+            }
+            OpCode::JumpIfEqual => {
+                let op1 = self.pop()?;
+                let op2 = self.pop()?;
+                let address = self.fetch_param(modes[0], 1)?;
+                if op1 == op2 {
+                    self.ip = address;
+                } else {
                     self.ip += 2;
                 }
-                OpCode::Pop => {
-                    self.stack.pop();
-                    self.ip += 1;
-                }
-                OpCode::Add => {
-                    let op1 = self.stack.pop().unwrap();
-                    let op2 = self.stack.pop().unwrap();
-                    let result = op1 + op2;
-                    self.stack.push(result);
-                    self.ip += 1;
-                }
-                OpCode::Sub => {
-                    let op1 = self.stack.pop().unwrap();
-                    let op2 = self.stack.pop().unwrap();
-                    let result = op2 - op1;
-                    self.stack.push(result);
-                    self.ip += 1;
-                }
-                OpCode::Mul => {
-                    let op1 = self.stack.pop().unwrap();
-                    let op2 = self.stack.pop().unwrap();
-                    let result = op1 * op2;
-                    self.stack.push(result);
-                    self.ip += 1;
-                }
-                OpCode::Div => {
-                    let op1 = self.stack.pop().unwrap();
-                    let op2 = self.stack.pop().unwrap();
-                    let result = op2 / op1;
-                    self.stack.push(result);
-                    self.ip += 1;
-                }
-                OpCode::Jump => {
-                    let address = self.memory[(self.ip + 1) as usize];
+            }
+            OpCode::JumpIfNotEqual => {
+                let op1 = self.pop()?;
+                let op2 = self.pop()?;
+                let address = self.fetch_param(modes[0], 1)?;
+                if op1 != op2 {
                     self.ip = address;
+                } else {
+                    self.ip += 2;
                 }
-                OpCode::JumpIfEqual => {
-                    let op1 = self.stack.pop().unwrap();
-                    let op2 = self.stack.pop().unwrap();
-                    let address = self.memory[(self.ip + 1) as usize];
-                    if op1 == op2 {
-                        self.ip = address;
-                    } else {
-                        self.ip += 2;
-                    }
-                }
-                OpCode::JumpIfNotEqual => {
-                    let op1 = self.stack.pop().unwrap();
-                    let op2 = self.stack.pop().unwrap();
-                    let address = self.memory[(self.ip + 1) as usize];
-                    if op1 != op2 {
-                        self.ip = address;
-                    } else {
-                        self.ip += 2;
-                    }
-                }
-                OpCode::JumpIfLessThan => {
-                    let op1 = self.stack.pop().unwrap();
-                    let op2 = self.stack.pop().unwrap();
-                    let address = self.memory[(self.ip + 1) as usize];
-                    if op1 < op2 {
-                        self.ip = address;
-                    } else {
-                        self.ip += 2;
-                    }
-                }
-                OpCode::JumpIfGreaterThan => {
-                    let op1 = self.stack.pop().unwrap();
-                    let op2 = self.stack.pop().unwrap();
-                    let address = self.memory[(self.ip + 1) as usize];
-                    if op1 > op2 {
-                        self.ip = address;
-                    } else {
-                        self.ip += 2;
-                    }
-                }
-                OpCode::JumpIfLessThanOrEqual => {
-                    let op1 = self.stack.pop().unwrap();
-                    let op2 = self.stack.pop().unwrap();
-                    let address = self.memory[(self.ip + 1) as usize];
-                    if op1 <= op2 {
-                        self.ip = address;
-                    } else {
-                        self.ip += 2;
-                    }
-                }
-                OpCode::JumpIfGreaterThanOrEqual => {
-                    let op1 = self.stack.pop().unwrap();
-                    let op2 = self.stack.pop().unwrap();
-                    let address = self.memory[(self.ip + 1) as usize];
-                    if op1 >= op2 {
-                        self.ip = address;
-                    } else {
-                        self.ip += 2;
-                    }
-                }
-                OpCode::Call => {
-                    let address = self.memory[(self.ip + 1) as usize];
-                    self.stack.push(self.ip + 2);
+            }
+            OpCode::JumpIfLessThan => {
+                let op1 = self.pop()?;
+                let op2 = self.pop()?;
+                let address = self.fetch_param(modes[0], 1)?;
+                if op1 < op2 {
                     self.ip = address;
+                } else {
+                    self.ip += 2;
                 }
-                OpCode::Return => {
-                    let address = self.stack.pop().unwrap();
+            }
+            OpCode::JumpIfGreaterThan => {
+                let op1 = self.pop()?;
+                let op2 = self.pop()?;
+                let address = self.fetch_param(modes[0], 1)?;
+                if op1 > op2 {
                     self.ip = address;
+                } else {
+                    self.ip += 2;
                 }
-                OpCode::Print => {
-                    let value = self.stack.last().unwrap();
-                    println!("{}", value);
-                    self.ip += 1;
+            }
+            OpCode::JumpIfLessThanOrEqual => {
+                let op1 = self.pop()?;
+                let op2 = self.pop()?;
+                let address = self.fetch_param(modes[0], 1)?;
+                if op1 <= op2 {
+                    self.ip = address;
+                } else {
+                    self.ip += 2;
                 }
-                OpCode::Store => {
-                    let address = self.memory[(self.ip + 1) as usize];
-                    let value = self.stack.pop().unwrap();
-                    self.memory[address as usize] = value;
+            }
+            OpCode::JumpIfGreaterThanOrEqual => {
+                let op1 = self.pop()?;
+                let op2 = self.pop()?;
+                let address = self.fetch_param(modes[0], 1)?;
+                if op1 >= op2 {
+                    self.ip = address;
+                } else {
                     self.ip += 2;
                 }
-                OpCode::Load => {
-                    let address = self.memory[(self.ip + 1) as usize];
-                    let value = self.memory[address as usize];
+            }
+            OpCode::Call => {
+                let address = self.fetch_param(modes[0], 1)?;
+                self.stack.push(self.ip + 2);
+                self.ip = address;
+            }
+            OpCode::Return => {
+                let address = self.pop()?;
+                self.ip = address;
+            }
+            OpCode::Print => {
+                let value = *self.stack.last().ok_or(VmError::StackUnderflow)?;
+                output.write(value);
+                self.ip += 1;
+            }
+            OpCode::Store => {
+                let address = self.write_addr(modes[0], 1)?;
+                let value = self.pop()?;
+                self.mem_write(address, value)?;
+                self.ip += 2;
+            }
+            OpCode::Load => {
+                let address = self.write_addr(modes[0], 1)?;
+                let value = self.mem_read(address)?;
+                self.stack.push(value);
+                self.ip += 2;
+            }
+            OpCode::AdjustRelativeBase => {
+                let offset = self.pop()?;
+                self.relative_base = self
+                    .relative_base
+                    .checked_add(offset)
+                    .ok_or(VmError::IntegerOverflow)?;
+                self.ip += 1;
+            }
+            OpCode::Input => match input.read() {
+                Some(value) => {
                     self.stack.push(value);
-                    self.ip += 2;
+                    self.ip += 1;
                 }
+                None => return Ok(StepOutcome::NeedsInput),
+            },
+            OpCode::Output => {
+                let value = self.pop()?;
+                output.write(value);
+                self.ip += 1;
+            }
+            OpCode::LoadReg => {
+                let r = self.reg_index(self.mem_read(self.ip + 1)?)?;
+                self.stack.push(self.registers[r]);
+                self.ip += 2;
+            }
+            OpCode::StoreReg => {
+                let r = self.reg_index(self.mem_read(self.ip + 1)?)?;
+                let value = self.pop()?;
+                self.registers[r] = value;
+                self.ip += 2;
+            }
+            OpCode::MovRegImm => {
+                let r = self.reg_index(self.mem_read(self.ip + 1)?)?;
+                let imm = self.mem_read(self.ip + 2)?;
+                self.registers[r] = imm;
+                self.ip += 3;
+            }
+            OpCode::LoadByte => {
+                let address = self.write_addr(modes[0], 1)?;
+                let value = sign_extend_bits(self.mem_read(address)?, 8);
+                self.stack.push(value);
+                self.ip += 2;
+            }
+            OpCode::LoadHalf => {
+                let address = self.write_addr(modes[0], 1)?;
+                let value = sign_extend_bits(self.mem_read(address)?, 16);
+                self.stack.push(value);
+                self.ip += 2;
+            }
+            OpCode::LoadWord => {
+                let address = self.write_addr(modes[0], 1)?;
+                let value = sign_extend_bits(self.mem_read(address)?, 32);
+                self.stack.push(value);
+                self.ip += 2;
+            }
+            OpCode::StoreByte => {
+                let address = self.write_addr(modes[0], 1)?;
+                let value = self.pop()?;
+                self.mem_write(address, truncate_bits(value, 8))?;
+                self.ip += 2;
+            }
+            OpCode::StoreHalf => {
+                let address = self.write_addr(modes[0], 1)?;
+                let value = self.pop()?;
+                self.mem_write(address, truncate_bits(value, 16))?;
+                self.ip += 2;
             }
+            OpCode::StoreWord => {
+                let address = self.write_addr(modes[0], 1)?;
+                let value = self.pop()?;
+                self.mem_write(address, truncate_bits(value, 32))?;
+                self.ip += 2;
+            }
+            OpCode::Syscall => {
+                let num = self.pop()?;
+                // Taken out of the map for the call so the handler can
+                // take `&mut self` without a conflicting borrow, then put
+                // back afterwards so it's available for the next call.
+                let mut handler = self
+                    .syscalls
+                    .remove(&num)
+                    .ok_or(VmError::InvalidSyscall(num))?;
+                let outcome = handler(self, input, output);
+                self.syscalls.insert(num, handler);
+                match outcome? {
+                    SyscallOutcome::Continue => {
+                        self.ip += 1;
+                    }
+                    SyscallOutcome::Halt => {
+                        return Ok(StepOutcome::Halted);
+                    }
+                    SyscallOutcome::NeedsInput => {
+                        self.stack.push(num);
+                        return Ok(StepOutcome::NeedsInput);
+                    }
+                }
+            }
+        }
+        Ok(StepOutcome::Stepped)
+    }
+}
+
+/// Magic bytes at the start of every `VmSnapshot` binary blob, identifying
+/// the format before any version-specific parsing is attempted.
+const SNAPSHOT_MAGIC: [u8; 4] = *b"RSVM";
+
+/// Binary format version written by `VmSnapshot::to_bytes`. Bump this if
+/// the layout changes, so `from_bytes` can reject blobs it can't parse.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// A point-in-time capture of everything needed to resume execution
+/// exactly where it left off: the program counter, relative base,
+/// register file, stack, memory, and gas used so far. Produced by
+/// `VirtualMachine::snapshot` and applied with `VirtualMachine::restore`,
+/// or persisted across process boundaries via `to_bytes`/`from_bytes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VmSnapshot {
+    ip: i64,
+    relative_base: i64,
+    registers: [i64; NUM_REGISTERS],
+    stack: Vec<i64>,
+    memory: Vec<i64>,
+    gas_used: u64,
+}
+
+/// Reads exactly `len` bytes starting at `*pos`, advancing `pos` past
+/// them, or `VmError::InvalidSnapshot` if the blob is too short.
+fn read_exact<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], VmError> {
+    let end = pos.checked_add(len).ok_or(VmError::InvalidSnapshot)?;
+    let slice = bytes.get(*pos..end).ok_or(VmError::InvalidSnapshot)?;
+    *pos = end;
+    Ok(slice)
+}
+
+fn read_u32(bytes: &[u8], pos: &mut usize) -> Result<u32, VmError> {
+    Ok(u32::from_le_bytes(read_exact(bytes, pos, 4)?.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], pos: &mut usize) -> Result<u64, VmError> {
+    Ok(u64::from_le_bytes(read_exact(bytes, pos, 8)?.try_into().unwrap()))
+}
+
+fn read_i64(bytes: &[u8], pos: &mut usize) -> Result<i64, VmError> {
+    Ok(i64::from_le_bytes(read_exact(bytes, pos, 8)?.try_into().unwrap()))
+}
+
+/// Checks that `len` 8-byte cells actually fit in the bytes remaining at
+/// `pos`, so a bogus oversized length prefix is rejected before it ever
+/// reaches an allocation.
+fn check_cells_fit(bytes: &[u8], pos: usize, len: usize) -> Result<(), VmError> {
+    let needed = len.checked_mul(8).ok_or(VmError::InvalidSnapshot)?;
+    if bytes.len() - pos < needed {
+        return Err(VmError::InvalidSnapshot);
+    }
+    Ok(())
+}
+
+impl VmSnapshot {
+    /// Serializes to a versioned binary blob: magic header, version, the
+    /// scalar fields, the fixed-size register file, then length-prefixed
+    /// stack/memory cells, all little-endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&SNAPSHOT_MAGIC);
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.ip.to_le_bytes());
+        bytes.extend_from_slice(&self.relative_base.to_le_bytes());
+        bytes.extend_from_slice(&self.gas_used.to_le_bytes());
+        for register in &self.registers {
+            bytes.extend_from_slice(&register.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.stack.len() as u64).to_le_bytes());
+        for cell in &self.stack {
+            bytes.extend_from_slice(&cell.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.memory.len() as u64).to_le_bytes());
+        for cell in &self.memory {
+            bytes.extend_from_slice(&cell.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Parses a blob written by `to_bytes`, rejecting anything with the
+    /// wrong magic header, an unknown version, or a truncated body.
+    pub fn from_bytes(bytes: &[u8]) -> Result<VmSnapshot, VmError> {
+        let mut pos = 0;
+        if read_exact(bytes, &mut pos, SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(VmError::InvalidSnapshot);
+        }
+        if read_u32(bytes, &mut pos)? != SNAPSHOT_VERSION {
+            return Err(VmError::InvalidSnapshot);
         }
+        let ip = read_i64(bytes, &mut pos)?;
+        let relative_base = read_i64(bytes, &mut pos)?;
+        let gas_used = read_u64(bytes, &mut pos)?;
+        let mut registers = [0i64; NUM_REGISTERS];
+        for register in registers.iter_mut() {
+            *register = read_i64(bytes, &mut pos)?;
+        }
+        let stack_len =
+            usize::try_from(read_u64(bytes, &mut pos)?).map_err(|_| VmError::InvalidSnapshot)?;
+        check_cells_fit(bytes, pos, stack_len)?;
+        let mut stack = Vec::with_capacity(stack_len);
+        for _ in 0..stack_len {
+            stack.push(read_i64(bytes, &mut pos)?);
+        }
+        let memory_len =
+            usize::try_from(read_u64(bytes, &mut pos)?).map_err(|_| VmError::InvalidSnapshot)?;
+        check_cells_fit(bytes, pos, memory_len)?;
+        let mut memory = Vec::with_capacity(memory_len);
+        for _ in 0..memory_len {
+            memory.push(read_i64(bytes, &mut pos)?);
+        }
+        Ok(VmSnapshot {
+            ip,
+            relative_base,
+            registers,
+            stack,
+            memory,
+            gas_used,
+        })
     }
 }
 
@@ -240,6 +825,23 @@ pub struct Assembler {
     pub machine_code: Vec<i64>,
 }
 
+/// Packs a base opcode and up to three parameter modes into a single
+/// opcode word, mirroring how `decode` splits it back apart.
+fn encode(op: OpCode, modes: &[ParamMode]) -> i64 {
+    let mut code = op as i64;
+    let mut place = 100;
+    for &mode in modes {
+        let digit = match mode {
+            ParamMode::Position => 0,
+            ParamMode::Immediate => 1,
+            ParamMode::Relative => 2,
+        };
+        code += digit * place;
+        place *= 10;
+    }
+    code
+}
+
 impl Assembler {
     pub fn new() -> Assembler {
         Assembler {
@@ -272,37 +874,42 @@ impl Assembler {
                 Instruction::Div => {
                     self.machine_code.push(OpCode::Div as i64);
                 }
-                Instruction::Jump(address) => {
-                    self.machine_code.push(OpCode::Jump as i64);
+                Instruction::Jump(address, mode) => {
+                    self.machine_code.push(encode(OpCode::Jump, &[mode]));
                     self.machine_code.push(address);
                 }
-                Instruction::JumpIfEqual(address) => {
-                    self.machine_code.push(OpCode::JumpIfEqual as i64);
+                Instruction::JumpIfEqual(address, mode) => {
+                    self.machine_code
+                        .push(encode(OpCode::JumpIfEqual, &[mode]));
                     self.machine_code.push(address);
                 }
-                Instruction::JumpIfNotEqual(address) => {
-                    self.machine_code.push(OpCode::JumpIfNotEqual as i64);
+                Instruction::JumpIfNotEqual(address, mode) => {
+                    self.machine_code
+                        .push(encode(OpCode::JumpIfNotEqual, &[mode]));
                     self.machine_code.push(address);
                 }
-                Instruction::JumpIfLessThan(address) => {
-                    self.machine_code.push(OpCode::JumpIfLessThan as i64);
+                Instruction::JumpIfLessThan(address, mode) => {
+                    self.machine_code
+                        .push(encode(OpCode::JumpIfLessThan, &[mode]));
                     self.machine_code.push(address);
                 }
-                Instruction::JumpIfGreaterThan(address) => {
-                    self.machine_code.push(OpCode::JumpIfGreaterThan as i64);
+                Instruction::JumpIfGreaterThan(address, mode) => {
+                    self.machine_code
+                        .push(encode(OpCode::JumpIfGreaterThan, &[mode]));
                     self.machine_code.push(address);
                 }
-                Instruction::JumpIfLessThanOrEqual(address) => {
-                    self.machine_code.push(OpCode::JumpIfLessThanOrEqual as i64);
+                Instruction::JumpIfLessThanOrEqual(address, mode) => {
+                    self.machine_code
+                        .push(encode(OpCode::JumpIfLessThanOrEqual, &[mode]));
                     self.machine_code.push(address);
                 }
-                Instruction::JumpIfGreaterThanOrEqual(address) => {
+                Instruction::JumpIfGreaterThanOrEqual(address, mode) => {
                     self.machine_code
-                        .push(OpCode::JumpIfGreaterThanOrEqual as i64);
+                        .push(encode(OpCode::JumpIfGreaterThanOrEqual, &[mode]));
                     self.machine_code.push(address);
                 }
-                Instruction::Call(address) => {
-                    self.machine_code.push(OpCode::Call as i64);
+                Instruction::Call(address, mode) => {
+                    self.machine_code.push(encode(OpCode::Call, &[mode]));
                     self.machine_code.push(address);
                 }
                 Instruction::Return => {
@@ -311,21 +918,74 @@ impl Assembler {
                 Instruction::Print => {
                     self.machine_code.push(OpCode::Print as i64);
                 }
-                Instruction::Store(address) => {
-                    self.machine_code.push(OpCode::Store as i64);
+                Instruction::Store(address, mode) => {
+                    self.machine_code.push(encode(OpCode::Store, &[mode]));
+                    self.machine_code.push(address);
+                }
+                Instruction::Load(address, mode) => {
+                    self.machine_code.push(encode(OpCode::Load, &[mode]));
+                    self.machine_code.push(address);
+                }
+                Instruction::AdjustRelativeBase => {
+                    self.machine_code.push(OpCode::AdjustRelativeBase as i64);
+                }
+                Instruction::Input => {
+                    self.machine_code.push(OpCode::Input as i64);
+                }
+                Instruction::Output => {
+                    self.machine_code.push(OpCode::Output as i64);
+                }
+                Instruction::LoadReg(r) => {
+                    self.machine_code.push(OpCode::LoadReg as i64);
+                    self.machine_code.push(r);
+                }
+                Instruction::StoreReg(r) => {
+                    self.machine_code.push(OpCode::StoreReg as i64);
+                    self.machine_code.push(r);
+                }
+                Instruction::MovRegImm(r, imm) => {
+                    self.machine_code.push(OpCode::MovRegImm as i64);
+                    self.machine_code.push(r);
+                    self.machine_code.push(imm);
+                }
+                Instruction::LoadByte(address, mode) => {
+                    self.machine_code.push(encode(OpCode::LoadByte, &[mode]));
                     self.machine_code.push(address);
                 }
-                Instruction::Load(address) => {
-                    self.machine_code.push(OpCode::Load as i64);
+                Instruction::LoadHalf(address, mode) => {
+                    self.machine_code.push(encode(OpCode::LoadHalf, &[mode]));
                     self.machine_code.push(address);
                 }
+                Instruction::LoadWord(address, mode) => {
+                    self.machine_code.push(encode(OpCode::LoadWord, &[mode]));
+                    self.machine_code.push(address);
+                }
+                Instruction::StoreByte(address, mode) => {
+                    self.machine_code.push(encode(OpCode::StoreByte, &[mode]));
+                    self.machine_code.push(address);
+                }
+                Instruction::StoreHalf(address, mode) => {
+                    self.machine_code.push(encode(OpCode::StoreHalf, &[mode]));
+                    self.machine_code.push(address);
+                }
+                Instruction::StoreWord(address, mode) => {
+                    self.machine_code.push(encode(OpCode::StoreWord, &[mode]));
+                    self.machine_code.push(address);
+                }
+                Instruction::Syscall => {
+                    self.machine_code.push(OpCode::Syscall as i64);
+                }
             }
         }
     }
 }
 
 mod tests {
-    use super::{Assembler, Instruction, OpCode, VirtualMachine};
+    use super::{
+        encode, Assembler, ComputeResult, Instruction, OpCode, ParamMode, StepOutcome,
+        SyscallOutcome, VirtualMachine, VmError, VmSnapshot, NUM_REGISTERS, SNAPSHOT_MAGIC,
+        SNAPSHOT_VERSION, SYSCALL_READ, SYSCALL_SHUTDOWN, SYSCALL_WRITE,
+    };
 
     #[test]
     fn test_assembler() {
@@ -364,10 +1024,491 @@ mod tests {
             OpCode::Halt as i64,
         ];
 
-        vm.execute();
+        vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
         assert_eq!(
             vm.stack.last().unwrap().to_owned(),
             i64::try_from(3).unwrap()
         );
     }
+
+    #[test]
+    fn test_relative_base_addressing() {
+        // Push 42, push base offset 5, adjust relative_base by it, then
+        // store/load through a relative address of 10 (-> memory[15]).
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&[
+            OpCode::Push as i64,
+            42,
+            OpCode::Push as i64,
+            5,
+            OpCode::AdjustRelativeBase as i64,
+            encode(OpCode::Store, &[ParamMode::Relative]),
+            10,
+            encode(OpCode::Load, &[ParamMode::Relative]),
+            10,
+            OpCode::Print as i64,
+            OpCode::Halt as i64,
+        ]);
+        vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
+        assert_eq!(vm.stack.last().unwrap().to_owned(), 42);
+    }
+
+    #[test]
+    fn test_adjust_relative_base_overflow_is_an_error_not_a_panic() {
+        // Push i64::MAX, adjust relative_base by it (now at the max), then
+        // push 1 and adjust again — the second adjustment overflows.
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&[
+            OpCode::Push as i64,
+            i64::MAX,
+            OpCode::AdjustRelativeBase as i64,
+            OpCode::Push as i64,
+            1,
+            OpCode::AdjustRelativeBase as i64,
+        ]);
+
+        assert_eq!(
+            vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Err(VmError::IntegerOverflow)
+        );
+    }
+
+    #[test]
+    fn test_position_mode_addressing() {
+        // Jump with a Position-mode operand of 20: the real jump target
+        // (7, landing on the Push below) is read indirectly out of
+        // memory[20] rather than used as a literal address.
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&[
+            encode(OpCode::Jump, &[ParamMode::Position]), // ip 0
+            20,
+            OpCode::Halt as i64, // ip 2, skipped if the jump works
+            0,
+            0,
+            0,
+            0,
+            OpCode::Push as i64, // ip 7, the jump target
+            55,
+            OpCode::Print as i64,
+            OpCode::Halt as i64,
+        ]);
+        vm.memory[20] = 7;
+        vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
+        assert_eq!(vm.stack.last().unwrap().to_owned(), 55);
+    }
+
+    #[test]
+    fn test_invalid_param_mode_is_an_error_not_a_panic() {
+        // Opcode word 307 is Jump (7) with mode digit 3, which names no
+        // ParamMode variant.
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&[307, 0]);
+
+        assert_eq!(
+            vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Err(VmError::InvalidParamMode(3))
+        );
+    }
+
+    #[test]
+    fn test_store_with_immediate_mode_is_an_error_not_a_panic() {
+        // Immediate mode names a value, not an address, so it isn't a
+        // valid write target for Store.
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&[
+            OpCode::Push as i64,
+            1,
+            encode(OpCode::Store, &[ParamMode::Immediate]),
+            20,
+        ]);
+
+        assert_eq!(
+            vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Err(VmError::InvalidWriteTarget)
+        );
+    }
+
+    #[test]
+    fn test_input_output_round_trip() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![
+            Instruction::Input,
+            Instruction::Output,
+            Instruction::Halt,
+        ]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        let mut input = vec![7];
+        let mut output = vec![];
+        vm.execute(&mut input, &mut output).unwrap();
+
+        assert_eq!(output, vec![7]);
+    }
+
+    #[test]
+    fn test_needs_input_suspends_and_resumes() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![
+            Instruction::Input,
+            Instruction::Output,
+            Instruction::Halt,
+        ]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        let mut input = vec![];
+        let mut output = vec![];
+        assert!(matches!(
+            vm.execute(&mut input, &mut output),
+            Ok(ComputeResult::NeedsInput)
+        ));
+        assert!(output.is_empty());
+
+        input.push(9);
+        assert!(matches!(
+            vm.execute(&mut input, &mut output),
+            Ok(ComputeResult::Halted)
+        ));
+        assert_eq!(output, vec![9]);
+    }
+
+    #[test]
+    fn test_division_by_zero_is_an_error_not_a_panic() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![
+            Instruction::Push(1),
+            Instruction::Push(0),
+            Instruction::Div,
+            Instruction::Halt,
+        ]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        assert_eq!(
+            vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Err(VmError::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn test_stack_underflow_is_an_error_not_a_panic() {
+        // Zero-size stack, so the very first pop has nothing to draw from.
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![Instruction::Add, Instruction::Halt]);
+        let mut vm = VirtualMachine::new(0, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        assert_eq!(
+            vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Err(VmError::StackUnderflow)
+        );
+    }
+
+    #[test]
+    fn test_invalid_opcode_is_an_error_not_a_panic() {
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&[99]);
+
+        assert_eq!(
+            vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Err(VmError::InvalidOpcode(99))
+        );
+    }
+
+    #[test]
+    fn test_gas_budget_halts_an_infinite_loop() {
+        // Jump(0) forever; each Jump costs 1 gas, so a budget of 3 allows
+        // exactly 3 dispatches before the 4th is rejected.
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![Instruction::Jump(0, ParamMode::Immediate)]);
+        let mut vm = VirtualMachine::new_with_gas(1024, 1024, 3);
+        vm.load_program(&assembler.machine_code);
+
+        assert_eq!(
+            vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Err(VmError::OutOfGas)
+        );
+        assert_eq!(vm.gas_used(), 3);
+    }
+
+    #[test]
+    fn test_register_file_round_trip() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![
+            Instruction::MovRegImm(0, 11),
+            Instruction::LoadReg(0),
+            Instruction::StoreReg(1),
+            Instruction::LoadReg(1),
+            Instruction::Print,
+            Instruction::Halt,
+        ]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
+        assert_eq!(vm.stack.last().unwrap().to_owned(), 11);
+    }
+
+    #[test]
+    fn test_invalid_register_is_an_error_not_a_panic() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![Instruction::MovRegImm(99, 1), Instruction::Halt]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        assert_eq!(
+            vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Err(VmError::InvalidRegister(99))
+        );
+    }
+
+    #[test]
+    fn test_byte_load_sign_extends_and_byte_store_truncates() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![
+            // Store -1 as a byte at address 20, then load it back; the
+            // stored cell should hold 0xFF (truncated) and the load should
+            // sign-extend it back to -1.
+            Instruction::Push(-1),
+            Instruction::StoreByte(20, ParamMode::Position),
+            Instruction::LoadByte(20, ParamMode::Position),
+            Instruction::Print,
+            Instruction::Halt,
+        ]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
+        assert_eq!(vm.stack.last().unwrap().to_owned(), -1);
+        assert_eq!(vm.memory[20], 0xFF);
+    }
+
+    #[test]
+    fn test_gas_used_is_tracked_without_a_limit() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![
+            Instruction::Push(2),
+            Instruction::Push(3),
+            Instruction::Mul,
+            Instruction::Halt,
+        ]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
+        // Push, Push, Mul (5), Halt.
+        assert_eq!(vm.gas_used(), 8);
+    }
+
+    #[test]
+    fn test_snapshot_restore_round_trip() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![
+            Instruction::Push(1),
+            Instruction::Push(2),
+            Instruction::Add,
+            Instruction::Push(3),
+            Instruction::Add,
+            Instruction::Print,
+            Instruction::Halt,
+        ]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        // Step past the first Add, snapshot, then run the rest twice from
+        // the same snapshot: both runs should land on the same result.
+        vm.step(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
+        vm.step(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
+        vm.step(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
+        let snapshot = vm.snapshot();
+
+        vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
+        assert_eq!(vm.stack.last().unwrap().to_owned(), 6);
+
+        vm.restore(&snapshot);
+        vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
+        assert_eq!(vm.stack.last().unwrap().to_owned(), 6);
+    }
+
+    #[test]
+    fn test_snapshot_to_bytes_from_bytes_round_trip() {
+        let mut vm = VirtualMachine::new(4, 4);
+        vm.load_program(&[OpCode::Push as i64, 42]);
+        vm.step(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
+
+        let bytes = vm.snapshot().to_bytes();
+        let restored = VmSnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(restored, vm.snapshot());
+    }
+
+    #[test]
+    fn test_snapshot_from_bytes_rejects_garbage() {
+        assert_eq!(
+            VmSnapshot::from_bytes(b"not a snapshot"),
+            Err(VmError::InvalidSnapshot)
+        );
+    }
+
+    #[test]
+    fn test_snapshot_from_bytes_rejects_oversized_length_prefix_without_panicking() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&SNAPSHOT_MAGIC);
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&0i64.to_le_bytes()); // ip
+        bytes.extend_from_slice(&0i64.to_le_bytes()); // relative_base
+        bytes.extend_from_slice(&0u64.to_le_bytes()); // gas_used
+        for _ in 0..NUM_REGISTERS {
+            bytes.extend_from_slice(&0i64.to_le_bytes());
+        }
+        // A stack length that claims far more cells than the blob could
+        // possibly contain must be rejected, not handed to Vec::with_capacity.
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+
+        assert_eq!(
+            VmSnapshot::from_bytes(&bytes),
+            Err(VmError::InvalidSnapshot)
+        );
+    }
+
+    #[test]
+    fn test_step_executes_exactly_one_instruction() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![
+            Instruction::Push(1),
+            Instruction::Push(2),
+            Instruction::Add,
+            Instruction::Halt,
+        ]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        assert_eq!(
+            vm.step(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Ok(StepOutcome::Stepped)
+        );
+        assert_eq!(vm.stack.last().unwrap().to_owned(), 1);
+        assert_eq!(
+            vm.step(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Ok(StepOutcome::Stepped)
+        );
+        assert_eq!(
+            vm.step(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Ok(StepOutcome::Stepped)
+        );
+        assert_eq!(vm.stack.last().unwrap().to_owned(), 3);
+        assert_eq!(
+            vm.step(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Ok(StepOutcome::Halted)
+        );
+    }
+
+    #[test]
+    fn test_builtin_write_syscall() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![
+            Instruction::Push(7),
+            Instruction::Push(SYSCALL_WRITE),
+            Instruction::Syscall,
+            Instruction::Halt,
+        ]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        let mut output = vec![];
+        vm.execute(&mut Vec::<i64>::new(), &mut output).unwrap();
+        assert_eq!(output, vec![7]);
+    }
+
+    #[test]
+    fn test_builtin_read_syscall_suspends_and_resumes() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![
+            Instruction::Push(SYSCALL_READ),
+            Instruction::Syscall,
+            Instruction::Print,
+            Instruction::Halt,
+        ]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        let mut input = vec![];
+        let mut output = vec![];
+        assert_eq!(
+            vm.execute(&mut input, &mut output),
+            Ok(ComputeResult::NeedsInput)
+        );
+
+        input.push(5);
+        assert_eq!(
+            vm.execute(&mut input, &mut output),
+            Ok(ComputeResult::Halted)
+        );
+        assert_eq!(output, vec![5]);
+    }
+
+    #[test]
+    fn test_builtin_shutdown_syscall_halts() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![
+            Instruction::Push(SYSCALL_SHUTDOWN),
+            Instruction::Syscall,
+            Instruction::Halt,
+        ]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        assert_eq!(
+            vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Ok(ComputeResult::Halted)
+        );
+        // Halted by the syscall (ip still at the Syscall instruction, never
+        // advancing past it), not by reaching the trailing `Halt`.
+        assert_eq!(vm.ip, 2);
+    }
+
+    #[test]
+    fn test_unregistered_syscall_is_an_error_not_a_panic() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![Instruction::Push(42), Instruction::Syscall]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+
+        assert_eq!(
+            vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new()),
+            Err(VmError::InvalidSyscall(42))
+        );
+    }
+
+    #[test]
+    fn test_host_can_register_a_custom_syscall() {
+        let mut assembler = Assembler::new();
+        assembler.assemble(vec![
+            Instruction::Push(100),
+            Instruction::Syscall,
+            Instruction::Halt,
+        ]);
+        let mut vm = VirtualMachine::new(1024, 1024);
+        vm.load_program(&assembler.machine_code);
+        vm.register_syscall(100, |vm, _input, _output| {
+            vm.stack.push(99);
+            Ok(SyscallOutcome::Continue)
+        });
+
+        vm.execute(&mut Vec::<i64>::new(), &mut Vec::<i64>::new())
+            .unwrap();
+        assert_eq!(vm.stack.last().unwrap().to_owned(), 99);
+    }
 }